@@ -0,0 +1,288 @@
+//! A small cfg()-style boolean expression language for rule conditions.
+//!
+//! Config-file rules can set a `condition` string combining
+//! `name_matches("...")`, `value_matches("...")`, and
+//! `value_entropy_above(4.0)` with `all(...)`, `any(...)`, and `not(...)`,
+//! e.g. `all(name_matches("KEY$"), value_entropy_above(4.0), not(value_matches("PUBLIC")))`.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use regex::RegexBuilder;
+
+use crate::secrets::has_high_entropy;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    NameMatches(String),
+    ValueMatches(String),
+    ValueEntropyAbove(f64),
+    All(Vec<Expr>),
+    Any(Vec<Expr>),
+    Not(Box<Expr>),
+}
+
+/// Which kind of predicate decided a successful match, for callers (see
+/// [`crate::report::MatchedBy`]) that need to report whether a rule matched
+/// by a variable's name or its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchSource {
+    Name,
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Expr, ParseError> {
+        let mut chars = input.chars().peekable();
+        let expr = parse_expr(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.peek().is_some() {
+            return Err(ParseError(format!(
+                "unexpected trailing input after expression in {input:?}"
+            )));
+        }
+        Ok(expr)
+    }
+
+    /// Evaluate this expression against one environment variable.
+    /// `value` is `None` when the value couldn't be decoded as UTF-8, in
+    /// which case `value_matches`/`value_entropy_above` never match.
+    pub fn evaluate(&self, name: &str, value: Option<&str>, min_secret_len: usize) -> bool {
+        match self {
+            Expr::NameMatches(pattern) => regex_is_match(pattern, name),
+            Expr::ValueMatches(pattern) => value.is_some_and(|v| regex_is_match(pattern, v)),
+            Expr::ValueEntropyAbove(threshold) => {
+                value.is_some_and(|v| has_high_entropy(v, min_secret_len, *threshold))
+            }
+            Expr::All(exprs) => exprs.iter().all(|e| e.evaluate(name, value, min_secret_len)),
+            Expr::Any(exprs) => exprs.iter().any(|e| e.evaluate(name, value, min_secret_len)),
+            Expr::Not(inner) => !inner.evaluate(name, value, min_secret_len),
+        }
+    }
+
+    /// Like [`Expr::evaluate`], but also reports which kind of predicate
+    /// decided the match: `Some(Value)` if the match depended on any
+    /// `value_matches`/`value_entropy_above` leaf, `Some(Name)` if it was
+    /// decided purely by `name_matches`, or `None` if the expression didn't
+    /// match at all.
+    pub fn matched_by(&self, name: &str, value: Option<&str>, min_secret_len: usize) -> Option<MatchSource> {
+        self.evaluate(name, value, min_secret_len)
+            .then(|| self.match_source(name, value, min_secret_len))
+    }
+
+    fn match_source(&self, name: &str, value: Option<&str>, min_secret_len: usize) -> MatchSource {
+        match self {
+            Expr::NameMatches(_) => MatchSource::Name,
+            Expr::ValueMatches(_) | Expr::ValueEntropyAbove(_) => MatchSource::Value,
+            Expr::Not(inner) => inner.match_source(name, value, min_secret_len),
+            // `All` is decided by the children that agree with its result:
+            // every child when it is true, only the false ones when it is
+            // false (under `not`). Any of those being value-based means the
+            // overall match is value-based.
+            Expr::All(exprs) => {
+                let outcome = self.evaluate(name, value, min_secret_len);
+                if exprs
+                    .iter()
+                    .filter(|e| e.evaluate(name, value, min_secret_len) == outcome)
+                    .any(|e| e.match_source(name, value, min_secret_len) == MatchSource::Value)
+                {
+                    MatchSource::Value
+                } else {
+                    MatchSource::Name
+                }
+            }
+            // `Any` is decided by the children that agree with its result:
+            // only the true ones when it is true, every child when it is
+            // false (under `not`).
+            Expr::Any(exprs) => {
+                let outcome = self.evaluate(name, value, min_secret_len);
+                if exprs
+                    .iter()
+                    .filter(|e| e.evaluate(name, value, min_secret_len) == outcome)
+                    .any(|e| e.match_source(name, value, min_secret_len) == MatchSource::Value)
+                {
+                    MatchSource::Value
+                } else {
+                    MatchSource::Name
+                }
+            }
+        }
+    }
+}
+
+fn regex_is_match(pattern: &str, haystack: &str) -> bool {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map(|re| re.is_match(haystack))
+        .unwrap_or(false)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_ident(chars: &mut Peekable<Chars>) -> String {
+    let mut ident = String::new();
+    while matches!(chars.peek(), Some(c) if c.is_alphanumeric() || *c == '_') {
+        ident.push(chars.next().unwrap());
+    }
+    ident
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), ParseError> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        other => Err(ParseError(format!("expected '{expected}', found {other:?}"))),
+    }
+}
+
+fn parse_string_literal(chars: &mut Peekable<Chars>) -> Result<String, ParseError> {
+    skip_whitespace(chars);
+    expect(chars, '"')?;
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some(c) => s.push(c),
+                None => return Err(ParseError("unterminated escape in string literal".to_string())),
+            },
+            Some(c) => s.push(c),
+            None => return Err(ParseError("unterminated string literal".to_string())),
+        }
+    }
+}
+
+fn parse_number_literal(chars: &mut Peekable<Chars>) -> Result<f64, ParseError> {
+    skip_whitespace(chars);
+    let mut s = String::new();
+    while matches!(chars.peek(), Some(c) if c.is_ascii_digit() || *c == '.' || *c == '-') {
+        s.push(chars.next().unwrap());
+    }
+    s.parse::<f64>()
+        .map_err(|_| ParseError(format!("invalid number literal {s:?}")))
+}
+
+fn parse_expr(chars: &mut Peekable<Chars>) -> Result<Expr, ParseError> {
+    skip_whitespace(chars);
+    let ident = parse_ident(chars);
+    expect(chars, '(')?;
+
+    let expr = match ident.as_str() {
+        "name_matches" => Expr::NameMatches(parse_string_literal(chars)?),
+        "value_matches" => Expr::ValueMatches(parse_string_literal(chars)?),
+        "value_entropy_above" => Expr::ValueEntropyAbove(parse_number_literal(chars)?),
+        "not" => Expr::Not(Box::new(parse_expr(chars)?)),
+        "all" => Expr::All(parse_expr_list(chars)?),
+        "any" => Expr::Any(parse_expr_list(chars)?),
+        other => return Err(ParseError(format!("unknown predicate or combinator {other:?}"))),
+    };
+
+    expect(chars, ')')?;
+    Ok(expr)
+}
+
+fn parse_expr_list(chars: &mut Peekable<Chars>) -> Result<Vec<Expr>, ParseError> {
+    let mut exprs = Vec::new();
+    skip_whitespace(chars);
+    if chars.peek() == Some(&')') {
+        return Ok(exprs);
+    }
+    loop {
+        exprs.push(parse_expr(chars)?);
+        skip_whitespace(chars);
+        match chars.peek() {
+            Some(',') => {
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+    Ok(exprs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_name_matches() {
+        assert_eq!(
+            Expr::parse(r#"name_matches("^MY_KEY$")"#).unwrap(),
+            Expr::NameMatches(String::from("^MY_KEY$"))
+        );
+    }
+
+    #[test]
+    fn test_parses_nested_combinators() {
+        let expr = Expr::parse(
+            r#"all(name_matches("KEY$"), value_entropy_above(4.0), not(value_matches("PUBLIC")))"#,
+        )
+        .unwrap();
+        assert_eq!(
+            expr,
+            Expr::All(vec![
+                Expr::NameMatches(String::from("KEY$")),
+                Expr::ValueEntropyAbove(4.0),
+                Expr::Not(Box::new(Expr::ValueMatches(String::from("PUBLIC")))),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_predicate() {
+        assert!(Expr::parse(r#"bogus("x")"#).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_all_and_not() {
+        let expr = Expr::parse(
+            r#"all(name_matches("_KEY$"), not(value_matches("^ssh-")))"#,
+        )
+        .unwrap();
+        assert!(expr.evaluate("MY_KEY", Some("random-token-value"), 20));
+        assert!(!expr.evaluate("MY_KEY", Some("ssh-rsa AAAA..."), 20));
+        assert!(!expr.evaluate("MY_OTHER", Some("random-token-value"), 20));
+    }
+
+    #[test]
+    fn test_matched_by_reports_value_source() {
+        let expr = Expr::parse(r#"value_matches("secret")"#).unwrap();
+        assert_eq!(
+            expr.matched_by("ANYTHING", Some("a-secret-value"), 20),
+            Some(MatchSource::Value)
+        );
+        assert_eq!(expr.matched_by("ANYTHING", Some("nothing-here"), 20), None);
+    }
+
+    #[test]
+    fn test_matched_by_reports_name_source() {
+        let expr = Expr::parse(r#"name_matches("_KEY$")"#).unwrap();
+        assert_eq!(expr.matched_by("MY_KEY", None, 20), Some(MatchSource::Name));
+    }
+
+    #[test]
+    fn test_matched_by_combinator_is_value_if_any_child_is() {
+        let expr = Expr::parse(r#"all(name_matches("_KEY$"), value_entropy_above(4.0))"#).unwrap();
+        assert_eq!(
+            expr.matched_by("MY_KEY", Some("kX8f2PqzR7mNc1WbT9yLgVs3"), 20),
+            Some(MatchSource::Value)
+        );
+    }
+}
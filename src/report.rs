@@ -0,0 +1,108 @@
+//! Structured records of what saferenv did to each environment variable,
+//! for `--report`/`--dry-run`.
+
+use serde::Serialize;
+
+/// The effective action taken on a variable. Distinct from [`crate::rules::RuleAction`]
+/// because a variable with no matching rule is reported as `Passthrough`
+/// (or `Unset`, under `--ignore-environment`) rather than left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportAction {
+    Keep,
+    Redact,
+    Unset,
+    Passthrough,
+    Mask,
+    Hash,
+    /// The variable's name couldn't be decoded as UTF-8, so no rule could
+    /// be matched against it; it's left untouched.
+    Skipped,
+}
+
+/// Which side of a rule's condition caused it to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchedBy {
+    Name,
+    Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportEntry {
+    pub key: String,
+    pub action: ReportAction,
+    pub rule: Option<String>,
+    pub layer: Option<String>,
+    pub matched_by: Option<MatchedBy>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Render a full report in the requested format.
+pub fn render(entries: &[ReportEntry], format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(entries).expect("report entries always serialize")
+        }
+        ReportFormat::Text => entries
+            .iter()
+            .map(render_text_line)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn render_text_line(entry: &ReportEntry) -> String {
+    match (&entry.rule, &entry.layer) {
+        (Some(rule), Some(layer)) => {
+            format!("{}: {:?} (rule '{}', {})", entry.key, entry.action, rule, layer)
+        }
+        _ => format!("{}: {:?}", entry.key, entry.action),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_entries() -> Vec<ReportEntry> {
+        vec![
+            ReportEntry {
+                key: String::from("MY_TOKEN"),
+                action: ReportAction::Redact,
+                rule: Some(String::from("generic_secret_token")),
+                layer: Some(String::from("builtin default")),
+                matched_by: Some(MatchedBy::Name),
+            },
+            ReportEntry {
+                key: String::from("PATH"),
+                action: ReportAction::Passthrough,
+                rule: None,
+                layer: None,
+                matched_by: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_render_json_round_trips_through_serde() {
+        let rendered = render(&sample_entries(), ReportFormat::Json);
+        let parsed: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed[0]["key"], "MY_TOKEN");
+        assert_eq!(parsed[0]["matched_by"], "name");
+        assert_eq!(parsed[1]["action"], "passthrough");
+    }
+
+    #[test]
+    fn test_render_text_includes_rule_and_layer() {
+        let rendered = render(&sample_entries(), ReportFormat::Text);
+        assert!(rendered.contains("MY_TOKEN: Redact (rule 'generic_secret_token', builtin default)"));
+        assert!(rendered.contains("PATH: Passthrough"));
+    }
+}
@@ -1,8 +1,24 @@
-#[derive(Debug, PartialEq, Eq, Clone)]
+use std::path::{Path, PathBuf};
+
+use log::{debug, trace, warn};
+use regex::RegexBuilder;
+use serde::Deserialize;
+
+use crate::expr::Expr;
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Rule {
     pub name: String,
     pub pattern: String,
     pub action: RuleAction,
+    pub layer: RuleLayer,
+    pub match_on: MatchOn,
+
+    /// An optional boolean expression (see [`crate::expr`]) that, when
+    /// present, fully determines whether this rule matches a variable and
+    /// takes over from `pattern`/`match_on`. Rules without one keep the
+    /// legacy behavior, for which `pattern` is sugar for `name_matches`.
+    pub condition: Option<Expr>,
 }
 
 // Types of actions that can be taken when a Rule matches
@@ -11,55 +27,590 @@ pub enum RuleAction {
     Keep,
     Redact,
     Unset,
+    /// Keep a configurable prefix/suffix of the value and replace the
+    /// middle with `*`, e.g. `AKIA****************XMPL`.
+    Mask,
+    /// Replace the value with a short stable digest of itself, so the same
+    /// secret is recognizable across environments without exposing it.
+    Hash,
+}
+
+/// What a [`Rule`] inspects to decide whether it matches an environment
+/// variable.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum MatchOn {
+    /// Match `pattern` against the variable's name. The historical, and
+    /// still default, behavior.
+    #[default]
+    Name,
+    /// Match `pattern` (when non-empty) and the built-in credential/entropy
+    /// detectors against the variable's value.
+    Value,
+    /// Match either of the above.
+    Either,
+}
+
+/// Where a [`Rule`] came from.
+///
+/// Rules are matched in list order and the first match wins, so
+/// higher-precedence layers must be pushed onto the list before
+/// lower-precedence ones by [`load_rules`]. From lowest to highest
+/// precedence: `BuiltinDefault` < `UserConfig` < `ProjectConfig` <
+/// `ExplicitConfig` < `Cli`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RuleLayer {
+    BuiltinDefault,
+    UserConfig,
+    ProjectConfig,
+    ExplicitConfig,
+    Cli,
+}
+
+impl std::fmt::Display for RuleLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            RuleLayer::BuiltinDefault => "builtin default",
+            RuleLayer::UserConfig => "user config",
+            RuleLayer::ProjectConfig => "project config",
+            RuleLayer::ExplicitConfig => "explicit config (--config)",
+            RuleLayer::Cli => "cli",
+        };
+        write!(f, "{label}")
+    }
+}
+
+/// Shape of a single `[[rule]]` entry in a rules config file, before it's
+/// tagged with the layer it was loaded from.
+#[derive(Debug, Deserialize)]
+struct RuleDef {
+    name: String,
+    #[serde(default)]
+    pattern: String,
+    action: RuleActionDef,
+    #[serde(default, rename = "match_on")]
+    match_on: MatchOnDef,
+    #[serde(default)]
+    condition: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RuleActionDef {
+    Keep,
+    Redact,
+    Unset,
+    Mask,
+    Hash,
+}
+
+impl From<RuleActionDef> for RuleAction {
+    fn from(value: RuleActionDef) -> Self {
+        match value {
+            RuleActionDef::Keep => RuleAction::Keep,
+            RuleActionDef::Redact => RuleAction::Redact,
+            RuleActionDef::Unset => RuleAction::Unset,
+            RuleActionDef::Mask => RuleAction::Mask,
+            RuleActionDef::Hash => RuleAction::Hash,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum MatchOnDef {
+    #[default]
+    Name,
+    Value,
+    Either,
 }
 
-pub fn load_rules(keep: &Vec<String>, unset: &Vec<String>) -> Vec<Rule> {
+impl From<MatchOnDef> for MatchOn {
+    fn from(value: MatchOnDef) -> Self {
+        match value {
+            MatchOnDef::Name => MatchOn::Name,
+            MatchOnDef::Value => MatchOn::Value,
+            MatchOnDef::Either => MatchOn::Either,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleDef>,
+}
+
+/// Load and parse rules from a single config file, tagging each with
+/// `layer`. A missing file is treated as "no rules from this layer", not an
+/// error, since the user config and project config paths are optional by
+/// design.
+fn load_rules_file(path: &Path, layer: RuleLayer) -> Vec<Rule> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            trace!("No rules loaded from {path:?}: {e}");
+            return Vec::new();
+        }
+    };
+
+    let parsed: RulesFile = match toml::from_str(&contents) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            warn!("Failed to parse rules config {path:?}: {e}");
+            return Vec::new();
+        }
+    };
+
+    debug!("Loaded {} rule(s) from {path:?} ({layer})", parsed.rule.len());
+    parsed
+        .rule
+        .into_iter()
+        .filter_map(|def| {
+            let condition = def.condition.as_deref().and_then(|raw| match Expr::parse(raw) {
+                Ok(expr) => Some(expr),
+                Err(e) => {
+                    warn!("Ignoring invalid condition for rule '{}' in {path:?}: {e}", def.name);
+                    None
+                }
+            });
+            let match_on: MatchOn = def.match_on.into();
+
+            // Without a condition, a rule falls back to matching `pattern`
+            // against the variable's name whenever `match_on` is name-based.
+            // An empty pattern there is a regex that matches everything, so
+            // a rule that omits `pattern` by mistake would silently apply
+            // to (and, per its action, potentially redact or unset) every
+            // variable in the environment.
+            if condition.is_none() && matches!(match_on, MatchOn::Name | MatchOn::Either) && def.pattern.is_empty() {
+                warn!(
+                    "Skipping rule '{}' in {path:?}: no pattern or condition given, and match_on is name-based (would match every variable)",
+                    def.name
+                );
+                return None;
+            }
+            // A non-empty pattern feeds straight into `RegexBuilder::...unwrap()`
+            // in the hot matching loop (for name-based matching) or into
+            // `detect_value_secret` (for value-based matching), so it must be
+            // validated here regardless of `match_on` — otherwise a bad
+            // pattern panics the name-based matching, and a bad
+            // `match_on = "value"` pattern silently never matches (only the
+            // built-in credential/entropy detectors still run for it).
+            if condition.is_none() && !def.pattern.is_empty() {
+                if let Err(e) = RegexBuilder::new(&def.pattern).case_insensitive(true).build() {
+                    warn!("Skipping rule '{}' in {path:?}: invalid pattern {:?}: {e}", def.name, def.pattern);
+                    return None;
+                }
+            }
+
+            Some(Rule {
+                name: def.name,
+                pattern: def.pattern,
+                action: def.action.into(),
+                layer,
+                match_on,
+                condition,
+            })
+        })
+        .collect()
+}
+
+/// The default per-user rules file, e.g. `~/.config/saferenv/rules.toml`.
+fn default_user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("saferenv").join("rules.toml"))
+}
+
+/// The default project-local rules file, `./.saferenv.toml`.
+fn default_project_config_path() -> PathBuf {
+    PathBuf::from(".saferenv.toml")
+}
+
+/// Build the merged, precedence-ordered list of rules.
+///
+/// Sources are layered in this order, lowest precedence first: built-in
+/// defaults, the user config, the project config, an explicit `--config`
+/// file, then the CLI's `--keep`/`--unset` flags. Since matching stops at
+/// the first rule that matches a variable, the list is built in the
+/// *reverse* of that order so higher-precedence layers are checked first.
+pub fn load_rules(
+    keep: &Vec<String>,
+    unset: &Vec<String>,
+    config: &Option<PathBuf>,
+    no_default_rules: bool,
+) -> Vec<Rule> {
+    load_rules_from(
+        keep,
+        unset,
+        config,
+        no_default_rules,
+        default_user_config_path(),
+        default_project_config_path(),
+    )
+}
+
+/// As [`load_rules`], but with the user/project config paths passed in
+/// explicitly instead of resolved from the real filesystem. Split out so
+/// tests can run hermetically regardless of what config files happen to
+/// exist on the machine running them.
+fn load_rules_from(
+    keep: &Vec<String>,
+    unset: &Vec<String>,
+    config: &Option<PathBuf>,
+    no_default_rules: bool,
+    user_config_path: Option<PathBuf>,
+    project_config_path: PathBuf,
+) -> Vec<Rule> {
     let mut rules: Vec<Rule> = Vec::new();
+
+    // CLI flags always win, so they're checked first. `key` is a literal
+    // variable name, not a regex, so it must be escaped before going into
+    // `pattern` — otherwise a name containing a regex metacharacter (e.g.
+    // `--keep 'FOO('`) would panic the `.unwrap()` in the matching loop.
     for key in keep {
         rules.push(Rule {
             name: String::from("cli_explicit_keep"),
-            pattern: format!("^{key}$"),
+            pattern: format!("^{}$", regex::escape(key)),
             action: RuleAction::Keep,
+            layer: RuleLayer::Cli,
+            match_on: MatchOn::Name,
+            condition: None,
         });
     }
     for key in unset {
         rules.push(Rule {
             name: String::from("cli_explicit_unset"),
-            pattern: format!("^{key}$"),
+            pattern: format!("^{}$", regex::escape(key)),
             action: RuleAction::Unset,
+            layer: RuleLayer::Cli,
+            match_on: MatchOn::Name,
+            condition: None,
         });
     }
 
-    // Add default rules
-    // Generic patterns
-    rules.push(Rule {
-        name: String::from("generic_secret"),
-        pattern: String::from(r"SECRETS?$"),
-        action: RuleAction::Redact,
-    });
-    rules.push(Rule {
-        name: String::from("generic_secret_token"),
-        pattern: String::from(r"TOKENS?$"),
-        action: RuleAction::Redact,
-    });
-    rules.push(Rule {
-        name: String::from("generic_secret_key"),
-        pattern: String::from(r"KEYS?$"),
-        action: RuleAction::Redact,
-    });
-    rules.push(Rule {
-        name: String::from("generic_password"),
-        pattern: String::from(r"PASSWORDS?$"),
-        action: RuleAction::Redact,
-    });
-    rules.push(Rule {
-        name: String::from("generic_password_short"),
-        pattern: String::from(r"(_|-)PW$"),
-        action: RuleAction::Redact,
-    });
-
-    // Specific patterns
-    // ...but then I realized that the generic patterns were pretty decent
+    if let Some(path) = config {
+        rules.extend(load_rules_file(path, RuleLayer::ExplicitConfig));
+    }
+
+    rules.extend(load_rules_file(&project_config_path, RuleLayer::ProjectConfig));
+
+    if let Some(user_path) = user_config_path {
+        rules.extend(load_rules_file(&user_path, RuleLayer::UserConfig));
+    }
+
+    if !no_default_rules {
+        // Generic patterns
+        rules.push(Rule {
+            name: String::from("generic_secret"),
+            pattern: String::from(r"SECRETS?$"),
+            action: RuleAction::Redact,
+            layer: RuleLayer::BuiltinDefault,
+            match_on: MatchOn::Name,
+            condition: None,
+        });
+        rules.push(Rule {
+            name: String::from("generic_secret_token"),
+            pattern: String::from(r"TOKENS?$"),
+            action: RuleAction::Redact,
+            layer: RuleLayer::BuiltinDefault,
+            match_on: MatchOn::Name,
+            condition: None,
+        });
+        rules.push(Rule {
+            name: String::from("generic_secret_key"),
+            pattern: String::from(r"KEYS?$"),
+            action: RuleAction::Redact,
+            layer: RuleLayer::BuiltinDefault,
+            match_on: MatchOn::Name,
+            condition: None,
+        });
+        rules.push(Rule {
+            name: String::from("generic_password"),
+            pattern: String::from(r"PASSWORDS?$"),
+            action: RuleAction::Redact,
+            layer: RuleLayer::BuiltinDefault,
+            match_on: MatchOn::Name,
+            condition: None,
+        });
+        rules.push(Rule {
+            name: String::from("generic_password_short"),
+            pattern: String::from(r"(_|-)PW$"),
+            action: RuleAction::Redact,
+            layer: RuleLayer::BuiltinDefault,
+            match_on: MatchOn::Name,
+            condition: None,
+        });
+
+        // Specific patterns
+        // ...but then I realized that the generic patterns were pretty decent
+
+        // Catches secrets in values regardless of what the variable is named,
+        // e.g. a CONFIG var holding an AWS key. Placed last so any
+        // name-based rule (including a user's explicit --keep) still wins.
+        rules.push(Rule {
+            name: String::from("generic_secret_value"),
+            pattern: String::new(),
+            action: RuleAction::Redact,
+            layer: RuleLayer::BuiltinDefault,
+            match_on: MatchOn::Value,
+            condition: None,
+        });
+    }
 
     rules
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A project/user config path guaranteed not to exist, so tests that
+    /// aren't exercising those layers stay hermetic regardless of what the
+    /// machine running them has lying around (e.g. a real
+    /// `~/.config/saferenv/rules.toml`).
+    fn missing_path(label: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "saferenv-test-missing-{label}-{:?}",
+            std::thread::current().id()
+        ))
+    }
+
+    #[test]
+    fn test_no_default_rules() {
+        let rules = load_rules_from(&vec![], &vec![], &None, true, None, missing_path("no-default-rules"));
+        assert!(rules.is_empty());
+    }
+
+    #[test]
+    fn test_cli_flags_outrank_defaults() {
+        let rules = load_rules_from(
+            &vec![String::from("MY_KEY")],
+            &vec![],
+            &None,
+            false,
+            None,
+            missing_path("cli-flags-outrank"),
+        );
+        // cli_explicit_keep must come before the builtin key rule so it wins
+        let keep_idx = rules.iter().position(|r| r.name == "cli_explicit_keep").unwrap();
+        let default_idx = rules.iter().position(|r| r.name == "generic_secret_key").unwrap();
+        assert!(keep_idx < default_idx);
+        assert_eq!(rules[keep_idx].layer, RuleLayer::Cli);
+    }
+
+    #[test]
+    fn test_cli_keep_and_unset_escape_regex_metacharacters() {
+        // `--keep`/`--unset` take literal variable names, not regexes, so a
+        // name containing a regex metacharacter must not produce a pattern
+        // that fails to compile (which would panic the matching loop).
+        let rules = load_rules_from(
+            &vec![String::from("FOO(BAR")],
+            &vec![String::from("BAZ[QUX")],
+            &None,
+            true,
+            None,
+            missing_path("cli-keep-unset-escaping"),
+        );
+        let keep = rules.iter().find(|r| r.name == "cli_explicit_keep").unwrap();
+        assert_eq!(keep.pattern, r"^FOO\(BAR$");
+        let unset = rules.iter().find(|r| r.name == "cli_explicit_unset").unwrap();
+        assert_eq!(unset.pattern, r"^BAZ\[QUX$");
+    }
+
+    #[test]
+    fn test_explicit_config_file_is_loaded_and_outranks_project_config() {
+        let dir = std::env::temp_dir().join(format!(
+            "saferenv-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("explicit.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            [[rule]]
+            name = "custom_keep"
+            pattern = "^MY_APP_.*$"
+            action = "keep"
+            "#,
+        )
+        .unwrap();
+
+        let rules = load_rules_from(
+            &vec![],
+            &vec![],
+            &Some(config_path.clone()),
+            true,
+            None,
+            missing_path("explicit-config"),
+        );
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].name, "custom_keep");
+        assert_eq!(rules[0].action, RuleAction::Keep);
+        assert_eq!(rules[0].layer, RuleLayer::ExplicitConfig);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_config_file_parses_mask_and_hash_actions() {
+        let dir = std::env::temp_dir().join(format!(
+            "saferenv-test-mask-hash-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("mask_hash.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            [[rule]]
+            name = "mask_it"
+            pattern = "^MASKED_.*$"
+            action = "mask"
+
+            [[rule]]
+            name = "hash_it"
+            pattern = "^HASHED_.*$"
+            action = "hash"
+            "#,
+        )
+        .unwrap();
+
+        let rules = load_rules_from(
+            &vec![],
+            &vec![],
+            &Some(config_path.clone()),
+            true,
+            None,
+            missing_path("mask-hash"),
+        );
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].action, RuleAction::Mask);
+        assert_eq!(rules[1].action, RuleAction::Hash);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_config_file_rejects_name_rule_with_no_pattern_or_condition() {
+        let dir = std::env::temp_dir().join(format!(
+            "saferenv-test-empty-pattern-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("empty_pattern.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            [[rule]]
+            name = "forgot_the_pattern"
+            action = "redact"
+
+            [[rule]]
+            name = "value_only_is_fine"
+            action = "redact"
+            match_on = "value"
+            "#,
+        )
+        .unwrap();
+
+        // The name-based rule with no pattern and no condition would match
+        // every variable, so it must be dropped. The value-based rule has
+        // no such footgun (an empty value pattern just defers to the
+        // built-in credential/entropy detectors) and should load fine.
+        let rules = load_rules_from(
+            &vec![],
+            &vec![],
+            &Some(config_path.clone()),
+            true,
+            None,
+            missing_path("empty-pattern"),
+        );
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].name, "value_only_is_fine");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_config_file_rejects_invalid_regex_pattern() {
+        let dir = std::env::temp_dir().join(format!(
+            "saferenv-test-invalid-regex-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("invalid_regex.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            [[rule]]
+            name = "broken_pattern"
+            pattern = "MY_KEY("
+            action = "redact"
+
+            [[rule]]
+            name = "valid_pattern"
+            pattern = "^MY_APP_.*$"
+            action = "keep"
+            "#,
+        )
+        .unwrap();
+
+        // An invalid regex must be rejected at load time (with a warning),
+        // not left to panic the `.unwrap()` in the matching loop.
+        let rules = load_rules_from(
+            &vec![],
+            &vec![],
+            &Some(config_path.clone()),
+            true,
+            None,
+            missing_path("invalid-regex"),
+        );
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].name, "valid_pattern");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_config_file_rejects_invalid_regex_pattern_for_value_based_rule() {
+        let dir = std::env::temp_dir().join(format!(
+            "saferenv-test-invalid-value-regex-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("invalid_value_regex.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            [[rule]]
+            name = "broken_value_pattern"
+            pattern = "AKIA("
+            action = "redact"
+            match_on = "value"
+
+            [[rule]]
+            name = "valid_value_pattern"
+            pattern = "^AKIA"
+            action = "redact"
+            match_on = "value"
+            "#,
+        )
+        .unwrap();
+
+        // A `match_on = "value"` rule's pattern feeds `detect_value_secret`
+        // just as surely as a name-based pattern feeds the matching loop, so
+        // it must be validated at load time too, rather than silently never
+        // matching any variable it's checked against.
+        let rules = load_rules_from(
+            &vec![],
+            &vec![],
+            &Some(config_path.clone()),
+            true,
+            None,
+            missing_path("invalid-value-regex"),
+        );
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].name, "valid_value_pattern");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}